@@ -54,6 +54,153 @@ fn try_swap_optimized(
     }
 }
 
+// 环状多分组轮换(3-opt):groups[i] 得到 groups[i+1] 原来的键,首尾相接成环,
+// 用来跳出两两交换(2-opt)改进不了的局部最优
+fn try_rotate_optimized(
+    &mut self,
+    ctx: &OptContext,
+    assignment: &mut [u8],
+    groups: &[usize],
+    temp: f64,
+    rng: &mut ThreadRng,
+) -> bool {
+    let n = groups.len();
+    if n < 2 || n > 4 {
+        return false;
+    }
+
+    let new_keys = rotation_target_keys(assignment, groups);
+
+    // 快速过滤:必须是真正的环(没有分组"轮换"到自己原来的键),且每个
+    // 分组都能在 dynamic_groups 中动态地接受它将获得的新键位
+    for i in 0..n {
+        if new_keys[i] == assignment[groups[i]] || !ctx.dynamic_groups[groups[i]].contains_key(new_keys[i]) {
+            return false;
+        }
+    }
+
+    let old_score = self.get_score(ctx);
+
+    // 合并所有涉及分组的受影响汉字,去重后统一建立一份快照,
+    // 而不是像两两交换那样只处理两个分组
+    let mut affected_chars = Vec::new();
+    for &group_idx in groups {
+        for &char_idx in &ctx.group_to_char_indices[group_idx] {
+            if !affected_chars.contains(&char_idx) {
+                affected_chars.push(char_idx);
+            }
+        }
+    }
+
+    let snapshot = self.create_swap_snapshot(&affected_chars);
+
+    // 一次性应用整个环的键位变更,再统一做一次增量更新
+    let old_keys: Vec<u8> = groups.iter().map(|&g| assignment[g]).collect();
+    for i in 0..n {
+        assignment[groups[i]] = new_keys[i];
+    }
+    self.update_swap_diff_fast(ctx, assignment, &affected_chars);
+
+    let new_score = self.get_score(ctx);
+    let delta = new_score - old_score;
+
+    if delta <= 0.0 || rng.gen::<f64>() < (-delta / temp).exp() {
+        true
+    } else {
+        // 回滚:整个环一起复原,不存在"部分接受"
+        for (i, &group_idx) in groups.iter().enumerate() {
+            assignment[group_idx] = old_keys[i];
+        }
+        self.restore_swap_snapshot(snapshot);
+        false
+    }
+}
+
+#[inline(always)]
+fn should_attempt_rotation(rng: &mut ThreadRng, rotation_probability: f64) -> bool {
+    rng.gen::<f64>() < rotation_probability
+}
+
+// 退火主循环每一步调用这里:多数情况下做一次普通的两两交换,
+// 以 self.rotation_attempt_probability 的概率改为尝试一次 2~4 个分组的环状轮换
+fn anneal_step(
+    &mut self,
+    ctx: &OptContext,
+    assignment: &mut [u8],
+    temp: f64,
+    rng: &mut ThreadRng,
+) -> bool {
+    let num_groups = ctx.dynamic_groups.len();
+
+    if should_attempt_rotation(rng, self.rotation_attempt_probability) {
+        if let Some(groups) = pick_rotation_groups(rng, num_groups) {
+            return self.try_rotate_optimized(ctx, assignment, &groups, temp, rng);
+        }
+    }
+
+    let r1 = rng.gen_range(0..num_groups);
+    let r2 = rng.gen_range(0..num_groups);
+    self.try_swap_optimized(ctx, assignment, r1, r2, temp, rng)
+}
+
+// 不足 2 个分组时没有可轮换的候选,返回 None 让调用方退回两两交换,
+// 避免 gen_range(2..=4.min(num_groups)) 在 num_groups < 2 时因空区间 panic
+fn pick_rotation_groups(rng: &mut ThreadRng, num_groups: usize) -> Option<Vec<usize>> {
+    if num_groups < 2 {
+        return None;
+    }
+    let rotation_len = rng.gen_range(2..=4.min(num_groups));
+    let mut groups = Vec::with_capacity(rotation_len);
+    while groups.len() < rotation_len {
+        let candidate = rng.gen_range(0..num_groups);
+        if !groups.contains(&candidate) {
+            groups.push(candidate);
+        }
+    }
+    Some(groups)
+}
+
+// groups[i] 在轮换后应拿到 groups[(i+1) % n] 现在的键位;与 ctx 无关,可独立测试
+fn rotation_target_keys(assignment: &[u8], groups: &[usize]) -> Vec<u8> {
+    let n = groups.len();
+    (0..n).map(|i| assignment[groups[(i + 1) % n]]).collect()
+}
+
+#[cfg(test)]
+mod rotation_tests {
+    use super::*;
+
+    #[test]
+    fn rotation_target_keys_cycles_forward() {
+        let assignment = vec![10, 20, 30, 40];
+        let groups = vec![0, 1, 2];
+        assert_eq!(rotation_target_keys(&assignment, &groups), vec![20, 30, 10]);
+    }
+
+    #[test]
+    fn pick_rotation_groups_returns_none_below_two_groups() {
+        let mut rng = rand::thread_rng();
+        assert!(pick_rotation_groups(&mut rng, 0).is_none());
+        assert!(pick_rotation_groups(&mut rng, 1).is_none());
+    }
+
+    #[test]
+    fn pick_rotation_groups_returns_distinct_in_range_groups() {
+        let mut rng = rand::thread_rng();
+        for num_groups in 2..=6 {
+            let groups = pick_rotation_groups(&mut rng, num_groups).unwrap();
+            assert!(groups.len() >= 2 && groups.len() <= 4);
+            assert!(groups.len() <= num_groups);
+            assert!(groups.iter().all(|&g| g < num_groups));
+
+            let mut sorted = groups.clone();
+            sorted.sort_unstable();
+            sorted.dedup();
+            assert_eq!(sorted.len(), groups.len());
+        }
+    }
+}
+
 #[inline(always)]
 fn create_swap_snapshot(&self, affected_chars: &[usize]) -> SwapSnapshot {
     let mut snapshot = SwapSnapshot {
@@ -79,8 +226,8 @@ fn create_swap_snapshot(&self, affected_chars: &[usize]) -> SwapSnapshot {
         
         // 保存相关桶的状态
         let code = self.current_codes[char_idx];
-        snapshot.buckets.push((code, self.buckets[code]));
-        snapshot.bucket_freqs.push((code, self.bucket_freqs[code]));
+        snapshot.buckets.push((code, self.buckets.count(code)));
+        snapshot.bucket_freqs.push((code, self.buckets.freq(code)));
     }
     
     snapshot
@@ -109,12 +256,10 @@ fn restore_swap_snapshot(&mut self, snapshot: SwapSnapshot) {
         self.current_equiv_sq_contrib[char_idx] = equiv_sq;
     }
     
-    // 恢复桶状态
-    for (code, count) in snapshot.buckets {
-        self.buckets[code] = count;
-    }
-    for (code, freq) in snapshot.bucket_freqs {
-        self.bucket_freqs[code] = freq;
+    // 恢复桶状态;buckets/bucket_freqs 按相同顺序记录同一批 code,
+    // 两两配对写回即可,不需要触碰整条全宽数组
+    for ((code, count), (_, freq)) in snapshot.buckets.into_iter().zip(snapshot.bucket_freqs.into_iter()) {
+        self.buckets.set(code, count, freq);
     }
 }
 
@@ -131,7 +276,7 @@ fn update_swap_diff_fast(&mut self, ctx: &OptContext, assignment: &[u8], affecte
         let old_code = self.current_codes[char_idx];
         let (old_keys, old_num_keys) = self.current_keys[char_idx];
         
-        let (new_code, new_keys, new_num_keys) = ctx.calc_code_and_keys(char_idx, assignment);
+        let (new_code, new_keys, new_num_keys) = self.calc_code_and_keys_cached(ctx, char_idx, assignment);
 
         if old_code == new_code {
             continue;
@@ -185,35 +330,253 @@ fn update_swap_diff_fast(&mut self, ctx: &OptContext, assignment: &[u8], affecte
     self.key_weighted_usage = local_key_usage;
 }
 
+#[inline(always)]
+fn code_cache_key(&self, ctx: &OptContext, char_idx: usize, assignment: &[u8]) -> CodeCacheKey {
+    // 一个汉字的编码只取决于它所属各分组当前的键位,而不是整个 assignment,
+    // 所以键可以直接从 char_to_groups 便宜地构造出来
+    let groups = &ctx.char_to_groups[char_idx];
+    let mut key_bytes = Vec::with_capacity(groups.len());
+    for &group_idx in groups {
+        key_bytes.push(assignment[group_idx]);
+    }
+    (char_idx, key_bytes)
+}
+
+#[inline(always)]
+fn calc_code_and_keys_cached(
+    &mut self,
+    ctx: &OptContext,
+    char_idx: usize,
+    assignment: &[u8],
+) -> (usize, [u8; MAX_PARTS], u8) {
+    let key = self.code_cache_key(ctx, char_idx, assignment);
+    if let Some(cached) = self.code_cache.get(&key) {
+        return cached;
+    }
+    let computed = ctx.calc_code_and_keys(char_idx, assignment);
+    self.code_cache.put(key, computed);
+    computed
+}
+
+#[inline(always)]
+fn code_cache_hit_rate(&self) -> f64 {
+    self.code_cache.hit_rate()
+}
+
+// 构造一份全新的退火状态;code_cache 容量、轮换概率和是否强制稀疏桶都来自 ctx,
+// 自动切换稀疏桶的阈值则是常量 BucketStore::SPARSE_THRESHOLD
+fn new(ctx: &OptContext, assignment: &[u8]) -> Self {
+    let char_count = ctx.char_infos.len();
+    let mut state = Self {
+        current_codes: vec![0usize; char_count],
+        current_keys: vec![([0u8; MAX_PARTS], 0u8); char_count],
+        current_equiv_contrib: vec![0.0; char_count],
+        current_equiv_sq_contrib: vec![0.0; char_count],
+        buckets: BucketStore::new(ctx.code_space_size, ctx.force_sparse_buckets),
+        total_collisions: 0,
+        collision_frequency: 0,
+        total_equiv_weighted: 0.0,
+        total_equiv_sq_weighted: 0.0,
+        key_weighted_usage: [0.0; EQUIV_TABLE_SIZE],
+        code_cache: CodeCache::new(ctx.code_cache_capacity),
+        rotation_attempt_probability: ctx.rotation_attempt_probability,
+    };
+
+    for char_idx in 0..char_count {
+        let (code, keys, num_keys) = ctx.calc_code_and_keys(char_idx, assignment);
+        let freq = ctx.char_infos[char_idx].frequency;
+        let freq_f = freq as f64;
+        let key_avg_equiv = ctx.calc_key_avg_equiv_inline(keys, num_keys);
+
+        state.current_codes[char_idx] = code;
+        state.current_keys[char_idx] = (keys, num_keys);
+        state.current_equiv_contrib[char_idx] = key_avg_equiv * freq_f;
+        state.current_equiv_sq_contrib[char_idx] = key_avg_equiv * key_avg_equiv * freq_f;
+
+        let count = state.buckets.count(code);
+        let existing_freq = state.buckets.freq(code);
+        if count >= 1 {
+            state.total_collisions += 1;
+            state.collision_frequency += freq;
+            if count == 1 {
+                state.collision_frequency += existing_freq;
+            }
+        }
+        state.buckets.set(code, count.wrapping_add(1), existing_freq + freq);
+
+        for j in 0..num_keys as usize {
+            state.key_weighted_usage[keys[j] as usize] += freq_f;
+        }
+    }
+
+    state
+}
+
+// 保存完整优化器状态,供中断后恢复;变长数组按"长度前缀 + 内容"逐段写入,
+// 魔数、版本号、标量和定长的 key_weighted_usage 直接写入,数值一律小端序
+fn save_checkpoint(&self, assignment: &[u8], temperature: f64, path: &Path) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::DirBuilder::new().recursive(true).create(dir)?;
+    }
+
+    let mut writer = BufWriter::new(File::create(path)?);
+
+    writer.write_all(CHECKPOINT_MAGIC)?;
+    writer.write_all(&CHECKPOINT_VERSION.to_le_bytes())?;
+
+    write_len_prefixed_usize_slice(&mut writer, &self.current_codes)?;
+    write_len_prefixed_keys_slice(&mut writer, &self.current_keys)?;
+    write_len_prefixed_f64_slice(&mut writer, &self.current_equiv_contrib)?;
+    write_len_prefixed_f64_slice(&mut writer, &self.current_equiv_sq_contrib)?;
+    self.buckets.write_to(&mut writer)?;
+
+    writer.write_all(&(self.total_collisions as u64).to_le_bytes())?;
+    writer.write_all(&self.collision_frequency.to_le_bytes())?;
+    writer.write_all(&self.total_equiv_weighted.to_le_bytes())?;
+    writer.write_all(&self.total_equiv_sq_weighted.to_le_bytes())?;
+
+    for &v in self.key_weighted_usage.iter() {
+        writer.write_all(&v.to_le_bytes())?;
+    }
+
+    write_len_prefixed_u8_slice(&mut writer, assignment)?;
+    writer.write_all(&temperature.to_le_bytes())?;
+
+    writer.flush()
+}
+
+// 读回一份检查点恢复到 self,并校验与 ctx 是否一致(汉字数量、分组数量);
+// 截断的文件、魔数/版本不符或长度字段被改动过的文件返回 Err,而不是 panic;
+// 各字段的取值(编码、键位、计数)不做校验
+fn load_checkpoint(&mut self, ctx: &OptContext, path: &Path) -> io::Result<(Vec<u8>, f64)> {
+    let file_len = fs::metadata(path)?.len();
+    let mut reader = BufReader::new(File::open(path)?);
+
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != CHECKPOINT_MAGIC {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not an lz_test checkpoint file"));
+    }
+
+    let mut version_buf = [0u8; 4];
+    reader.read_exact(&mut version_buf)?;
+    let version = u32::from_le_bytes(version_buf);
+    if version != CHECKPOINT_VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unsupported checkpoint version {version}"),
+        ));
+    }
+
+    let current_codes = read_len_prefixed_usize_vec(&mut reader, file_len)?;
+    let current_keys = read_len_prefixed_keys_vec(&mut reader, file_len)?;
+    let current_equiv_contrib = read_len_prefixed_f64_vec(&mut reader, file_len)?;
+    let current_equiv_sq_contrib = read_len_prefixed_f64_vec(&mut reader, file_len)?;
+    let buckets = BucketStore::read_from(&mut reader, file_len)?;
+
+    let mut scalar_buf = [0u8; 8];
+    reader.read_exact(&mut scalar_buf)?;
+    let total_collisions = u64::from_le_bytes(scalar_buf) as usize;
+    reader.read_exact(&mut scalar_buf)?;
+    let collision_frequency = u64::from_le_bytes(scalar_buf);
+    reader.read_exact(&mut scalar_buf)?;
+    let total_equiv_weighted = f64::from_le_bytes(scalar_buf);
+    reader.read_exact(&mut scalar_buf)?;
+    let total_equiv_sq_weighted = f64::from_le_bytes(scalar_buf);
+
+    let mut key_weighted_usage = [0f64; EQUIV_TABLE_SIZE];
+    for slot in key_weighted_usage.iter_mut() {
+        reader.read_exact(&mut scalar_buf)?;
+        *slot = f64::from_le_bytes(scalar_buf);
+    }
+
+    let assignment = read_len_prefixed_u8_vec(&mut reader, file_len)?;
+    reader.read_exact(&mut scalar_buf)?;
+    let temperature = f64::from_le_bytes(scalar_buf);
+
+    let char_count = ctx.char_infos.len();
+    if current_codes.len() != char_count {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "checkpoint character count does not match this OptContext",
+        ));
+    }
+    if current_keys.len() != char_count {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "checkpoint key count does not match this OptContext",
+        ));
+    }
+    if current_equiv_contrib.len() != char_count {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "checkpoint equiv-contribution count does not match this OptContext",
+        ));
+    }
+    if current_equiv_sq_contrib.len() != char_count {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "checkpoint equiv-sq-contribution count does not match this OptContext",
+        ));
+    }
+    if let BucketStore::Dense { counts, freqs } = &buckets {
+        if counts.len() != ctx.code_space_size || freqs.len() != ctx.code_space_size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "checkpoint bucket array size does not match this OptContext",
+            ));
+        }
+    }
+    if assignment.len() != ctx.dynamic_groups.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "checkpoint group count does not match this OptContext",
+        ));
+    }
+
+    self.current_codes = current_codes;
+    self.current_keys = current_keys;
+    self.current_equiv_contrib = current_equiv_contrib;
+    self.current_equiv_sq_contrib = current_equiv_sq_contrib;
+    self.buckets = buckets;
+    self.total_collisions = total_collisions;
+    self.collision_frequency = collision_frequency;
+    self.total_equiv_weighted = total_equiv_weighted;
+    self.total_equiv_sq_weighted = total_equiv_sq_weighted;
+    self.key_weighted_usage = key_weighted_usage;
+
+    Ok((assignment, temperature))
+}
+
 #[inline(always)]
 fn update_buckets_for_char(&mut self, old_code: usize, new_code: usize, freq: u64) -> (isize, i64) {
     let mut collision_delta = 0isize;
     let mut freq_delta = 0i64;
-    
+
     // 处理旧编码
-    let old_count = self.buckets[old_code];
+    let old_count = self.buckets.count(old_code);
+    let old_bucket_freq = self.buckets.freq(old_code);
     if old_count > 1 {
         collision_delta -= 1;
         freq_delta -= freq as i64;
         if old_count == 2 {
-            freq_delta -= (self.bucket_freqs[old_code] - freq) as i64;
+            freq_delta -= (old_bucket_freq - freq) as i64;
         }
     }
-    self.buckets[old_code] = old_count.wrapping_sub(1);
-    self.bucket_freqs[old_code] -= freq;
+    self.buckets.set(old_code, old_count.wrapping_sub(1), old_bucket_freq - freq);
 
     // 处理新编码
-    let new_count = self.buckets[new_code];
+    let new_count = self.buckets.count(new_code);
+    let new_bucket_freq = self.buckets.freq(new_code);
     if new_count >= 1 {
         collision_delta += 1;
         freq_delta += freq as i64;
         if new_count == 1 {
-            freq_delta += self.bucket_freqs[new_code] as i64;
+            freq_delta += new_bucket_freq as i64;
         }
     }
-    self.buckets[new_code] = new_count.wrapping_add(1);
-    self.bucket_freqs[new_code] += freq;
-    
+    self.buckets.set(new_code, new_count.wrapping_add(1), new_bucket_freq + freq);
+
     (collision_delta, freq_delta)
 }
 
@@ -231,3 +594,612 @@ struct SwapSnapshot {
     total_equiv_sq_weighted: f64,
     key_weighted_usage: [f64; EQUIV_TABLE_SIZE],
 }
+
+// calc_code_and_keys 结果缓存的键:汉字索引 + 其所属各分组当前键位的字节序列
+type CodeCacheKey = (usize, Vec<u8>);
+
+// calc_code_and_keys 结果缓存的值:(code, keys, num_keys)
+type CodeCacheValue = (usize, [u8; MAX_PARTS], u8);
+
+// LRU 链表节点,prev/next 用 Vec 下标而非裸指针表达,避免 unsafe
+struct CodeCacheNode {
+    key: CodeCacheKey,
+    value: CodeCacheValue,
+    prev: Option<usize>,
+    next: Option<usize>,
+}
+
+// 有界 LRU 缓存,命中时跳过重新计算;容量由 OptContext::code_cache_capacity 决定
+struct CodeCache {
+    nodes: Vec<CodeCacheNode>,
+    index: HashMap<CodeCacheKey, usize>,
+    head: Option<usize>,
+    tail: Option<usize>,
+    free_list: Vec<usize>,
+    capacity: usize,
+    hits: u64,
+    misses: u64,
+}
+
+impl CodeCache {
+    fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        Self {
+            nodes: Vec::with_capacity(capacity),
+            index: HashMap::with_capacity(capacity),
+            head: None,
+            tail: None,
+            free_list: Vec::new(),
+            capacity,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    fn get(&mut self, key: &CodeCacheKey) -> Option<CodeCacheValue> {
+        if let Some(&idx) = self.index.get(key) {
+            self.hits += 1;
+            self.move_to_front(idx);
+            Some(self.nodes[idx].value)
+        } else {
+            self.misses += 1;
+            None
+        }
+    }
+
+    fn put(&mut self, key: CodeCacheKey, value: CodeCacheValue) {
+        if let Some(&idx) = self.index.get(&key) {
+            self.nodes[idx].value = value;
+            self.move_to_front(idx);
+            return;
+        }
+
+        if self.index.len() >= self.capacity {
+            self.evict_tail();
+        }
+
+        let idx = if let Some(free_idx) = self.free_list.pop() {
+            self.nodes[free_idx] = CodeCacheNode {
+                key: key.clone(),
+                value,
+                prev: None,
+                next: self.head,
+            };
+            free_idx
+        } else {
+            self.nodes.push(CodeCacheNode {
+                key: key.clone(),
+                value,
+                prev: None,
+                next: self.head,
+            });
+            self.nodes.len() - 1
+        };
+
+        if let Some(head_idx) = self.head {
+            self.nodes[head_idx].prev = Some(idx);
+        }
+        self.head = Some(idx);
+        if self.tail.is_none() {
+            self.tail = Some(idx);
+        }
+        self.index.insert(key, idx);
+    }
+
+    fn move_to_front(&mut self, idx: usize) {
+        if self.head == Some(idx) {
+            return;
+        }
+        let (prev, next) = (self.nodes[idx].prev, self.nodes[idx].next);
+        if let Some(prev_idx) = prev {
+            self.nodes[prev_idx].next = next;
+        }
+        if let Some(next_idx) = next {
+            self.nodes[next_idx].prev = prev;
+        }
+        if self.tail == Some(idx) {
+            self.tail = prev;
+        }
+        self.nodes[idx].prev = None;
+        self.nodes[idx].next = self.head;
+        if let Some(head_idx) = self.head {
+            self.nodes[head_idx].prev = Some(idx);
+        }
+        self.head = Some(idx);
+    }
+
+    fn evict_tail(&mut self) {
+        if let Some(tail_idx) = self.tail {
+            let prev = self.nodes[tail_idx].prev;
+            if let Some(prev_idx) = prev {
+                self.nodes[prev_idx].next = None;
+            }
+            self.tail = prev;
+            if self.head == Some(tail_idx) {
+                self.head = None;
+            }
+            self.index.remove(&self.nodes[tail_idx].key);
+            self.free_list.push(tail_idx);
+        }
+    }
+
+    fn hit_rate(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            0.0
+        } else {
+            self.hits as f64 / total as f64
+        }
+    }
+
+    fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    fn misses(&self) -> u64 {
+        self.misses
+    }
+}
+
+#[cfg(test)]
+mod code_cache_tests {
+    use super::*;
+
+    fn key(char_idx: usize, bytes: &[u8]) -> CodeCacheKey {
+        (char_idx, bytes.to_vec())
+    }
+
+    fn value(code: usize) -> CodeCacheValue {
+        (code, [0u8; MAX_PARTS], 1)
+    }
+
+    #[test]
+    fn get_on_empty_cache_is_a_miss() {
+        let mut cache = CodeCache::new(2);
+        assert!(cache.get(&key(0, &[1])).is_none());
+        assert_eq!(cache.hits(), 0);
+        assert_eq!(cache.misses(), 1);
+    }
+
+    #[test]
+    fn put_then_get_is_a_hit() {
+        let mut cache = CodeCache::new(2);
+        cache.put(key(0, &[1]), value(100));
+        assert_eq!(cache.get(&key(0, &[1])), Some(value(100)));
+        assert_eq!(cache.hits(), 1);
+        assert_eq!(cache.misses(), 0);
+        assert_eq!(cache.hit_rate(), 1.0);
+    }
+
+    #[test]
+    fn evicts_least_recently_used_when_over_capacity() {
+        let mut cache = CodeCache::new(2);
+        cache.put(key(0, &[1]), value(100));
+        cache.put(key(1, &[2]), value(200));
+        // 访问一次 key(0, ...),让它重新成为最近使用的,key(1, ...) 变成最久未使用
+        assert!(cache.get(&key(0, &[1])).is_some());
+        cache.put(key(2, &[3]), value(300));
+
+        assert!(cache.get(&key(1, &[2])).is_none());
+        assert_eq!(cache.get(&key(0, &[1])), Some(value(100)));
+        assert_eq!(cache.get(&key(2, &[3])), Some(value(300)));
+    }
+
+    #[test]
+    fn put_on_existing_key_updates_value_without_growing() {
+        let mut cache = CodeCache::new(2);
+        cache.put(key(0, &[1]), value(100));
+        cache.put(key(0, &[1]), value(999));
+        assert_eq!(cache.nodes.len(), 1);
+        assert_eq!(cache.get(&key(0, &[1])), Some(value(999)));
+    }
+}
+
+// 简化版 ahash:固定随机种子搭配几轮乘法异或混合,避免 SipHash 在纯数字键上的额外开销
+struct AHasher {
+    state: u64,
+}
+
+impl AHasher {
+    const SEED: u64 = 0x9E37_79B9_7F4A_7C15; // 黄金比例常数作固定随机种子
+
+    fn new() -> Self {
+        Self { state: Self::SEED }
+    }
+
+    #[inline(always)]
+    fn mix(x: u64) -> u64 {
+        let mut x = x.wrapping_mul(0xFF51_AFD7_ED55_8CCD);
+        x ^= x >> 33;
+        x = x.wrapping_mul(0xC4CE_B9FE_1A85_EC53);
+        x ^= x >> 33;
+        x
+    }
+}
+
+impl Hasher for AHasher {
+    #[inline(always)]
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.state = Self::mix(self.state ^ b as u64);
+        }
+    }
+
+    #[inline(always)]
+    fn write_usize(&mut self, i: usize) {
+        self.state = Self::mix(self.state ^ i as u64);
+    }
+
+    #[inline(always)]
+    fn finish(&self) -> u64 {
+        self.state
+    }
+}
+
+#[derive(Default, Clone)]
+struct BuildAHasher;
+
+impl BuildHasher for BuildAHasher {
+    type Hasher = AHasher;
+
+    fn build_hasher(&self) -> AHasher {
+        AHasher::new()
+    }
+}
+
+// 编码空间很大但实际占用稀疏时的桶存储:只记录非零桶,按编码空间大小或
+// 显式 builder 标志自动选择,见 BucketStore::new
+enum BucketStore {
+    Dense {
+        counts: Vec<u16>,
+        freqs: Vec<u64>,
+    },
+    Sparse {
+        entries: HashMap<usize, (u16, u64), BuildAHasher>,
+    },
+}
+
+impl BucketStore {
+    // 编码空间规模超过该阈值时,默认自动切换为稀疏表示
+    const SPARSE_THRESHOLD: usize = 1 << 20;
+
+    fn new(code_space: usize, force_sparse: Option<bool>) -> Self {
+        let use_sparse = force_sparse.unwrap_or(code_space > Self::SPARSE_THRESHOLD);
+        if use_sparse {
+            BucketStore::Sparse {
+                entries: HashMap::with_hasher(BuildAHasher),
+            }
+        } else {
+            BucketStore::Dense {
+                counts: vec![0u16; code_space],
+                freqs: vec![0u64; code_space],
+            }
+        }
+    }
+
+    #[inline(always)]
+    fn count(&self, code: usize) -> u16 {
+        match self {
+            BucketStore::Dense { counts, .. } => counts[code],
+            BucketStore::Sparse { entries } => entries.get(&code).map_or(0, |&(count, _)| count),
+        }
+    }
+
+    #[inline(always)]
+    fn freq(&self, code: usize) -> u64 {
+        match self {
+            BucketStore::Dense { freqs, .. } => freqs[code],
+            BucketStore::Sparse { entries } => entries.get(&code).map_or(0, |&(_, freq)| freq),
+        }
+    }
+
+    #[inline(always)]
+    fn set(&mut self, code: usize, count: u16, freq: u64) {
+        match self {
+            BucketStore::Dense { counts, freqs } => {
+                counts[code] = count;
+                freqs[code] = freq;
+            }
+            BucketStore::Sparse { entries } => {
+                if count == 0 && freq == 0 {
+                    entries.remove(&code);
+                } else {
+                    entries.insert(code, (count, freq));
+                }
+            }
+        }
+    }
+
+    // 标签字节区分稠密/稀疏,供 read_from 还原
+    fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        match self {
+            BucketStore::Dense { counts, freqs } => {
+                writer.write_all(&[0u8])?;
+                writer.write_all(&(counts.len() as u64).to_le_bytes())?;
+                for &count in counts {
+                    writer.write_all(&count.to_le_bytes())?;
+                }
+                for &freq in freqs {
+                    writer.write_all(&freq.to_le_bytes())?;
+                }
+            }
+            BucketStore::Sparse { entries } => {
+                writer.write_all(&[1u8])?;
+                writer.write_all(&(entries.len() as u64).to_le_bytes())?;
+                for (&code, &(count, freq)) in entries {
+                    writer.write_all(&(code as u64).to_le_bytes())?;
+                    writer.write_all(&count.to_le_bytes())?;
+                    writer.write_all(&freq.to_le_bytes())?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn read_from<R: Read>(reader: &mut R, file_len: u64) -> io::Result<Self> {
+        let mut tag = [0u8; 1];
+        reader.read_exact(&mut tag)?;
+        let raw_len = read_section_len(reader)?;
+
+        match tag[0] {
+            0 => {
+                let len = checked_section_len(raw_len, 2 + 8, file_len)?;
+                let mut counts = Vec::new();
+                try_reserve_io(&mut counts, len)?;
+                let mut count_buf = [0u8; 2];
+                for _ in 0..len {
+                    reader.read_exact(&mut count_buf)?;
+                    counts.push(u16::from_le_bytes(count_buf));
+                }
+                let mut freqs = Vec::new();
+                try_reserve_io(&mut freqs, len)?;
+                let mut freq_buf = [0u8; 8];
+                for _ in 0..len {
+                    reader.read_exact(&mut freq_buf)?;
+                    freqs.push(u64::from_le_bytes(freq_buf));
+                }
+                Ok(BucketStore::Dense { counts, freqs })
+            }
+            1 => {
+                let len = checked_section_len(raw_len, 8 + 2 + 8, file_len)?;
+                let mut entries = HashMap::with_hasher(BuildAHasher);
+                entries
+                    .try_reserve(len)
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+                let mut code_buf = [0u8; 8];
+                let mut count_buf = [0u8; 2];
+                let mut freq_buf = [0u8; 8];
+                for _ in 0..len {
+                    reader.read_exact(&mut code_buf)?;
+                    reader.read_exact(&mut count_buf)?;
+                    reader.read_exact(&mut freq_buf)?;
+                    entries.insert(
+                        u64::from_le_bytes(code_buf) as usize,
+                        (u16::from_le_bytes(count_buf), u64::from_le_bytes(freq_buf)),
+                    );
+                }
+                Ok(BucketStore::Sparse { entries })
+            }
+            other => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unknown bucket store tag {other} in checkpoint"),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod bucket_store_tests {
+    use super::*;
+
+    #[test]
+    fn fresh_store_has_empty_buckets() {
+        let dense = BucketStore::new(8, Some(false));
+        let sparse = BucketStore::new(8, Some(true));
+        assert_eq!(dense.count(3), 0);
+        assert_eq!(dense.freq(3), 0);
+        assert_eq!(sparse.count(3), 0);
+        assert_eq!(sparse.freq(3), 0);
+    }
+
+    #[test]
+    fn dense_and_sparse_agree_under_the_same_mutations() {
+        let mut dense = BucketStore::new(16, Some(false));
+        let mut sparse = BucketStore::new(16, Some(true));
+
+        let mutations: &[(usize, u16, u64)] = &[(2, 1, 5), (2, 2, 9), (7, 1, 3), (2, 0, 0), (15, 1, 1)];
+        for &(code, count, freq) in mutations {
+            dense.set(code, count, freq);
+            sparse.set(code, count, freq);
+        }
+
+        for code in 0..16 {
+            assert_eq!(dense.count(code), sparse.count(code), "count mismatch at code {code}");
+            assert_eq!(dense.freq(code), sparse.freq(code), "freq mismatch at code {code}");
+        }
+    }
+
+    #[test]
+    fn sparse_store_drops_entry_once_zeroed() {
+        let mut sparse = BucketStore::new(1 << 24, Some(true));
+        sparse.set(42, 3, 10);
+        assert_eq!(sparse.count(42), 3);
+        sparse.set(42, 0, 0);
+        assert_eq!(sparse.count(42), 0);
+        assert_eq!(sparse.freq(42), 0);
+        if let BucketStore::Sparse { entries } = &sparse {
+            assert!(!entries.contains_key(&42));
+        }
+    }
+
+    #[test]
+    fn auto_selects_sparse_above_threshold() {
+        let above_threshold = BucketStore::new(BucketStore::SPARSE_THRESHOLD + 1, None);
+        assert!(matches!(above_threshold, BucketStore::Sparse { .. }));
+
+        let below_threshold = BucketStore::new(16, None);
+        assert!(matches!(below_threshold, BucketStore::Dense { .. }));
+    }
+}
+
+// 魔数 + 版本号,load_checkpoint 用来快速拒绝不是本优化器写出的文件或不兼容的旧版本
+const CHECKPOINT_MAGIC: &[u8; 8] = b"LZOPTCKP";
+const CHECKPOINT_VERSION: u32 = 1;
+
+fn write_len_prefixed_u8_slice<W: Write>(writer: &mut W, data: &[u8]) -> io::Result<()> {
+    writer.write_all(&(data.len() as u64).to_le_bytes())?;
+    writer.write_all(data)
+}
+
+fn read_len_prefixed_u8_vec<R: Read>(reader: &mut R, file_len: u64) -> io::Result<Vec<u8>> {
+    let len = checked_section_len(read_section_len(reader)?, 1, file_len)?;
+    let mut buf = Vec::new();
+    try_reserve_io(&mut buf, len)?;
+    buf.resize(len, 0);
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn read_section_len<R: Read>(reader: &mut R) -> io::Result<usize> {
+    let mut len_buf = [0u8; 8];
+    reader.read_exact(&mut len_buf)?;
+    Ok(u64::from_le_bytes(len_buf) as usize)
+}
+
+// 一个被翻转了一位的长度字段不应该让 Vec::with_capacity/vec! 直接因分配过大而 panic/OOM,
+// 所以先按"文件里最多能装下多少个这么大的元素"校验一遍,再用 try_reserve 兜底
+fn checked_section_len(len: usize, element_size: usize, file_len: u64) -> io::Result<usize> {
+    let element_size = (element_size as u64).max(1);
+    if len as u64 > file_len / element_size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "checkpoint section length exceeds file size",
+        ));
+    }
+    Ok(len)
+}
+
+fn try_reserve_io<T>(vec: &mut Vec<T>, additional: usize) -> io::Result<()> {
+    vec.try_reserve_exact(additional)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))
+}
+
+fn write_len_prefixed_usize_slice<W: Write>(writer: &mut W, data: &[usize]) -> io::Result<()> {
+    writer.write_all(&(data.len() as u64).to_le_bytes())?;
+    for &v in data {
+        writer.write_all(&(v as u64).to_le_bytes())?;
+    }
+    Ok(())
+}
+
+fn read_len_prefixed_usize_vec<R: Read>(reader: &mut R, file_len: u64) -> io::Result<Vec<usize>> {
+    let len = checked_section_len(read_section_len(reader)?, 8, file_len)?;
+    let mut out = Vec::new();
+    try_reserve_io(&mut out, len)?;
+    let mut buf = [0u8; 8];
+    for _ in 0..len {
+        reader.read_exact(&mut buf)?;
+        out.push(u64::from_le_bytes(buf) as usize);
+    }
+    Ok(out)
+}
+
+fn write_len_prefixed_f64_slice<W: Write>(writer: &mut W, data: &[f64]) -> io::Result<()> {
+    writer.write_all(&(data.len() as u64).to_le_bytes())?;
+    for &v in data {
+        writer.write_all(&v.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+fn read_len_prefixed_f64_vec<R: Read>(reader: &mut R, file_len: u64) -> io::Result<Vec<f64>> {
+    let len = checked_section_len(read_section_len(reader)?, 8, file_len)?;
+    let mut out = Vec::new();
+    try_reserve_io(&mut out, len)?;
+    let mut buf = [0u8; 8];
+    for _ in 0..len {
+        reader.read_exact(&mut buf)?;
+        out.push(f64::from_le_bytes(buf));
+    }
+    Ok(out)
+}
+
+fn write_len_prefixed_keys_slice<W: Write>(writer: &mut W, data: &[([u8; MAX_PARTS], u8)]) -> io::Result<()> {
+    writer.write_all(&(data.len() as u64).to_le_bytes())?;
+    for (parts, num_keys) in data {
+        writer.write_all(parts)?;
+        writer.write_all(&[*num_keys])?;
+    }
+    Ok(())
+}
+
+fn read_len_prefixed_keys_vec<R: Read>(reader: &mut R, file_len: u64) -> io::Result<Vec<([u8; MAX_PARTS], u8)>> {
+    let len = checked_section_len(read_section_len(reader)?, MAX_PARTS + 1, file_len)?;
+    let mut out = Vec::new();
+    try_reserve_io(&mut out, len)?;
+    for _ in 0..len {
+        let mut parts = [0u8; MAX_PARTS];
+        reader.read_exact(&mut parts)?;
+        let mut num_keys_buf = [0u8; 1];
+        reader.read_exact(&mut num_keys_buf)?;
+        out.push((parts, num_keys_buf[0]));
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod checkpoint_format_tests {
+    use super::*;
+
+    #[test]
+    fn u8_slice_round_trips() {
+        let mut buf = Vec::new();
+        write_len_prefixed_u8_slice(&mut buf, &[1, 2, 3, 4]).unwrap();
+        let restored = read_len_prefixed_u8_vec(&mut &buf[..], buf.len() as u64).unwrap();
+        assert_eq!(restored, vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn f64_slice_round_trips() {
+        let mut buf = Vec::new();
+        write_len_prefixed_f64_slice(&mut buf, &[1.5, -2.25, 0.0]).unwrap();
+        let restored = read_len_prefixed_f64_vec(&mut &buf[..], buf.len() as u64).unwrap();
+        assert_eq!(restored, vec![1.5, -2.25, 0.0]);
+    }
+
+    #[test]
+    fn dense_bucket_store_round_trips() {
+        let mut store = BucketStore::new(8, Some(false));
+        store.set(2, 3, 42);
+        store.set(5, 1, 7);
+
+        let mut buf = Vec::new();
+        store.write_to(&mut buf).unwrap();
+        let restored = BucketStore::read_from(&mut &buf[..], buf.len() as u64).unwrap();
+        assert_eq!(restored.count(2), 3);
+        assert_eq!(restored.freq(2), 42);
+        assert_eq!(restored.count(5), 1);
+        assert_eq!(restored.freq(5), 7);
+    }
+
+    #[test]
+    fn sparse_bucket_store_round_trips() {
+        let mut store = BucketStore::new(1 << 24, Some(true));
+        store.set(1000, 2, 99);
+
+        let mut buf = Vec::new();
+        store.write_to(&mut buf).unwrap();
+        let restored = BucketStore::read_from(&mut &buf[..], buf.len() as u64).unwrap();
+        assert_eq!(restored.count(1000), 2);
+        assert_eq!(restored.freq(1000), 99);
+    }
+
+    #[test]
+    fn corrupted_length_is_rejected_not_oom() {
+        let mut buf = Vec::new();
+        write_len_prefixed_u8_slice(&mut buf, &[1, 2, 3]).unwrap();
+        buf[0..8].copy_from_slice(&u64::MAX.to_le_bytes());
+        let result = read_len_prefixed_u8_vec(&mut &buf[..], buf.len() as u64);
+        assert!(result.is_err());
+    }
+}